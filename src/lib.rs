@@ -1,127 +1,330 @@
-// < begin copyright > 
+// < begin copyright >
 // Copyright Ryan Marcus 2018
-// 
+//
 // This file is part of incremental_radix.
-// 
+//
 // incremental_radix is free software: you can redistribute it and/or modify
 // it under the terms of the GNU General Public License as published by
 // the Free Software Foundation, either version 3 of the License, or
 // (at your option) any later version.
-// 
+//
 // incremental_radix is distributed in the hope that it will be useful,
 // but WITHOUT ANY WARRANTY; without even the implied warranty of
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU General Public License for more details.
-// 
+//
 // You should have received a copy of the GNU General Public License
 // along with incremental_radix.  If not, see <http://www.gnu.org/licenses/>.
-// 
-// < end copyright > 
+//
+// < end copyright >
 extern crate rand;
 
+#[cfg(feature = "rayon")]
+extern crate rayon;
+
 use std::cmp;
+use std::mem;
+
+#[cfg(feature = "rayon")]
+use std::cell::UnsafeCell;
+#[cfg(feature = "rayon")]
+use self::rayon::prelude::*;
+
+// base-256 digits by default: cuts a 64-bit key down to 8 passes instead
+// of 64 one-bit passes.
+const DEFAULT_DIGIT_BITS: u8 = 8;
+
+/// Maps a sortable type onto an order-preserving unsigned key that the
+/// radix passes can operate on directly. Implement this for any type you
+/// want to pass to `IncrementalSorter::new`, or supply an ad-hoc mapping
+/// via `IncrementalSorter::with_key`.
+///
+/// Keys are carried as `usize`, so the built-in `u64`/`i64`/`f64`
+/// impls below assume a 64-bit `usize` (asserted in `from_keys`); on a
+/// target where `usize` is narrower, those impls would silently truncate
+/// the high bits of the key.
+pub trait RadixKey {
+    fn radix_key(&self) -> usize;
+}
+
+impl RadixKey for usize {
+    fn radix_key(&self) -> usize {
+        *self
+    }
+}
+
+macro_rules! unsigned_radix_key {
+    ($t:ty) => {
+        impl RadixKey for $t {
+            fn radix_key(&self) -> usize {
+                *self as usize
+            }
+        }
+    }
+}
+
+unsigned_radix_key!(u8);
+unsigned_radix_key!(u16);
+unsigned_radix_key!(u32);
+unsigned_radix_key!(u64);
+
+// signed integers sort correctly as unsigned once the sign bit is
+// flipped: that maps the most negative value to all-zeros and the most
+// positive value to all-ones, preserving order.
+macro_rules! signed_radix_key {
+    ($t:ty, $u:ty) => {
+        impl RadixKey for $t {
+            fn radix_key(&self) -> usize {
+                let sign_bit = 1 as $u << (<$u>::min_value().count_zeros() - 1);
+                ((*self as $u) ^ sign_bit) as usize
+            }
+        }
+    }
+}
+
+signed_radix_key!(i8, u8);
+signed_radix_key!(i16, u16);
+signed_radix_key!(i32, u32);
+signed_radix_key!(i64, u64);
+signed_radix_key!(isize, usize);
+
+// IEEE-754 floats sort correctly as unsigned once their bit pattern is
+// transformed: negative values (sign bit set) have every bit flipped so
+// that more-negative values get smaller keys, while non-negative values
+// only have their sign bit set so they sort above every negative value.
+// NaNs have an all-ones exponent and a non-zero mantissa, so positive
+// NaNs sort above +inf and negative NaNs sort below -inf.
+macro_rules! float_radix_key {
+    ($t:ty, $u:ty) => {
+        impl RadixKey for $t {
+            fn radix_key(&self) -> usize {
+                let bits = self.to_bits();
+                let sign_bit = 1 as $u << (<$u>::min_value().count_zeros() - 1);
+                let key = if bits & sign_bit != 0 { !bits } else { bits | sign_bit };
+                key as usize
+            }
+        }
+    }
+}
+
+float_radix_key!(f32, u32);
+float_radix_key!(f64, u64);
 
 enum SorterState {
     Unprepared,
     Counting,
+    PrefixSum,
     ComputeIndexes,
     MoveItems,
     Finished
 }
 
-pub struct IncrementalSorter {
+pub struct IncrementalSorter<T> {
     iterations_per_call: usize,
-    to_sort: Vec<usize>,
+    to_sort: Vec<T>,
     state: SorterState,
 
-    // overall sort progress
-    max_digit_index: u8,
+    // the unsigned radix key for each item in to_sort, in lockstep with
+    // it: move_items permutes both vectors together.
+    keys: Vec<usize>,
+
+    // width (in bits) of each radix digit, and the number of buckets
+    // (2^digit_bits) that implies
+    digit_bits: u8,
+    num_buckets: usize,
+
+    // the surviving digit positions to scan, as found by prepare(): any
+    // position whose bits are identical across every key is left out.
+    // digit_index is an index into this vector, not a digit position
+    // itself -- get_digit() does that translation.
+    digit_positions: Vec<u8>,
     digit_index: u8,
 
+    // set by prepare(): true if the input was already non-decreasing, in
+    // which case no radix work is needed at all
+    pre_sorted: bool,
+
     // set by compute_indexes, used by move_items
     new_indexes: Vec<usize>,
 
     // shared
     loop_index: usize,
 
-    // storage for compute_indexes
-    true_count: usize,
-    false_count: usize,
+    // histogram of digit values, built by bucket_counts and turned into
+    // per-bucket start offsets by prefix_sum; compute_indexes then walks
+    // offsets forward as a per-bucket cursor.
+    histogram: Vec<usize>,
+    offsets: Vec<usize>,
 
-    // set by bucket_counts, used by compute_indexes
-    accumulator: usize
+    // running total kept while prefix_sum walks the histogram
+    accumulator: usize,
+
+    // second buffer used only by the rayon-backed out-of-place scatter
+    #[cfg(feature = "rayon")]
+    scratch_to_sort: Vec<T>,
+    #[cfg(feature = "rayon")]
+    scratch_keys: Vec<usize>
 }
 
-impl IncrementalSorter {
-    pub fn new(to_sort: Vec<usize>) -> IncrementalSorter {
-        let len = to_sort.len();
-        return IncrementalSorter { to_sort, state: SorterState::Unprepared,
-                                   new_indexes: Vec::with_capacity(len),
-                                   max_digit_index: 0, digit_index: 0,
-                                   loop_index: 0, accumulator: 0,
-                                   true_count: 0, false_count: 0,
-                                   iterations_per_call: 32};
+impl<T> IncrementalSorter<T> {
+    pub fn new(to_sort: Vec<T>) -> IncrementalSorter<T> where T: RadixKey {
+        let keys = to_sort.iter().map(RadixKey::radix_key).collect();
+        return IncrementalSorter::from_keys(to_sort, keys, DEFAULT_DIGIT_BITS);
     }
 
-    pub fn with_iterations_per_call(to_sort: Vec<usize>, iterations_per_call: usize) -> IncrementalSorter {
+    /// Sorts `to_sort` by the order-preserving unsigned key that `key_fn`
+    /// derives from each item, e.g. sorting a `Vec<Person>` by
+    /// `|p| p.age`.
+    pub fn with_key<K: RadixKey>(to_sort: Vec<T>, key_fn: impl Fn(&T) -> K) -> IncrementalSorter<T> {
+        let keys = to_sort.iter().map(|itm| key_fn(itm).radix_key()).collect();
+        return IncrementalSorter::from_keys(to_sort, keys, DEFAULT_DIGIT_BITS);
+    }
+
+    pub fn with_iterations_per_call(to_sort: Vec<T>, iterations_per_call: usize) -> IncrementalSorter<T> where T: RadixKey {
         let mut to_return = IncrementalSorter::new(to_sort);
         to_return.iterations_per_call = iterations_per_call;
         return to_return;
     }
 
+    pub fn with_digit_bits(to_sort: Vec<T>, digit_bits: u8) -> IncrementalSorter<T> where T: RadixKey {
+        let keys = to_sort.iter().map(RadixKey::radix_key).collect();
+        return IncrementalSorter::from_keys(to_sort, keys, digit_bits);
+    }
+
+    fn from_keys(to_sort: Vec<T>, keys: Vec<usize>, digit_bits: u8) -> IncrementalSorter<T> {
+        // the RadixKey impls for u64/i64/f64 assume a 64-bit usize
+        // and would silently truncate keys on a narrower target.
+        debug_assert_eq!(mem::size_of::<usize>(), 8);
+
+        let len = to_sort.len();
+        let num_buckets = 1usize << digit_bits;
+        return IncrementalSorter { to_sort, keys, state: SorterState::Unprepared,
+                                   new_indexes: Vec::with_capacity(len),
+                                   digit_bits, num_buckets,
+                                   digit_positions: Vec::new(), digit_index: 0,
+                                   pre_sorted: false,
+                                   loop_index: 0, accumulator: 0,
+                                   histogram: vec![0; num_buckets],
+                                   offsets: vec![0; num_buckets],
+                                   #[cfg(feature = "rayon")]
+                                   scratch_to_sort: Vec::new(),
+                                   #[cfg(feature = "rayon")]
+                                   scratch_keys: Vec::new(),
+                                   iterations_per_call: 32};
+    }
+
     pub fn prepare(&mut self) {
         if let SorterState::Unprepared = self.state {
+            // a single pass over the keys: track the bitwise AND and OR
+            // of every key (a bit position where they agree is identical
+            // across the whole input and doesn't need a radix pass) and
+            // whether the keys are already non-decreasing.
+            let mut and_acc = usize::max_value();
+            let mut or_acc = 0usize;
+            let mut pre_sorted = true;
+            let mut previous = 0usize;
+            for (i, &key) in self.keys.iter().enumerate() {
+                and_acc &= key;
+                or_acc |= key;
+                if i > 0 && key < previous {
+                    pre_sorted = false;
+                }
+                previous = key;
+            }
+
+            self.pre_sorted = pre_sorted;
+            if pre_sorted {
+                // already sorted (or nothing to sort): skip all radix work
+                self.state = SorterState::Finished;
+                return;
+            }
+
+            let varying_bits = and_acc ^ or_acc;
             let total_bits = usize::min_value().count_zeros() + usize::min_value().count_ones();
-            let fewest_leading_zeros = self.to_sort.iter().map(|itm| itm.leading_zeros()).min().unwrap();
-            self.max_digit_index = (total_bits - fewest_leading_zeros) as u8;
+            let fewest_leading_zeros = or_acc.leading_zeros();
+            let bits_needed = (total_bits - fewest_leading_zeros) as usize;
+            let digit_bits = self.digit_bits as usize;
+            let num_digits = (bits_needed + digit_bits - 1) / digit_bits;
+
+            self.digit_positions = (0..num_digits as u8)
+                .filter(|&d| {
+                    let shift = d as usize * digit_bits;
+                    let digit_mask = (self.num_buckets - 1) << shift;
+                    varying_bits & digit_mask != 0
+                })
+                .collect();
+
             self.state = SorterState::Counting;
             return;
         }
 
         panic!("Call to IncrementalSorter prepare when not in the unprepared state");
-       
+
+    }
+
+    /// Number of radix digit passes `prepare()` determined are actually
+    /// needed, after leaving out any position whose bits don't vary
+    /// across the whole input. Only meaningful after `prepare()`.
+    pub fn digit_passes(&self) -> usize {
+        self.digit_positions.len()
+    }
+
+    /// True if `prepare()` found the input already non-decreasing and
+    /// skipped all radix work. Only meaningful after `prepare()`.
+    pub fn was_pre_sorted(&self) -> bool {
+        self.pre_sorted
     }
 
-    fn get_bit(idx: u8, itm: usize) -> bool {
-        itm & (1 << idx) != 0
+    // extracts the current digit (a value in [0, num_buckets)) from key
+    fn get_digit(&self, key: usize) -> usize {
+        let shift = self.digit_positions[self.digit_index as usize] as usize * self.digit_bits as usize;
+        (key >> shift) & (self.num_buckets - 1)
     }
 
-    // returns how many items in the "false" bucket
+    // tallies a histogram of digit values over the next chunk of elements
     fn bucket_counts(&mut self) -> bool {
-        let idx = self.digit_index;
         let start = self.loop_index;
-        let stop = cmp::min(start + self.iterations_per_call, self.to_sort.len());
+        let stop = cmp::min(start + self.iterations_per_call, self.keys.len());
 
-        let count = self.to_sort[start..stop].iter()
-            .filter(|&&itm| !IncrementalSorter::get_bit(idx, itm))
-            .count();
+        for &key in self.keys[start..stop].iter() {
+            let digit = self.get_digit(key);
+            self.histogram[digit] += 1;
+        }
 
-        self.accumulator += count;
         self.loop_index = stop;
-        return stop == self.to_sort.len();
+        return stop == self.keys.len();
+    }
+
+    // turns the histogram into per-bucket start offsets via a running
+    // prefix sum, chunked just like every other phase
+    fn prefix_sum(&mut self) -> bool {
+        let start = self.loop_index;
+        let stop = cmp::min(start + self.iterations_per_call, self.num_buckets);
+
+        for bucket in start..stop {
+            self.offsets[bucket] = self.accumulator;
+            self.accumulator += self.histogram[bucket];
+        }
+
+        self.loop_index = stop;
+        return stop == self.num_buckets;
     }
 
     fn compute_indexes(&mut self) -> bool {
-        // first, compute the new index of each element in the vector.
+        // compute the new index of each element in the vector, using
+        // offsets as a running per-bucket cursor.
         let start = self.loop_index;
-        let stop = cmp::min(start + self.iterations_per_call, self.to_sort.len());
-        
-        for &item in self.to_sort[start..stop].iter() {
-            if IncrementalSorter::get_bit(self.digit_index, item) {
-                // it goes in the true bin
-                self.new_indexes.push(self.true_count);
-                self.true_count += 1;
-            } else {
-                // it goes in the false bin
-                debug_assert!(self.false_count < self.accumulator);
-                self.new_indexes.push(self.false_count);
-                self.false_count += 1;
-            }
+        let stop = cmp::min(start + self.iterations_per_call, self.keys.len());
+
+        for &key in self.keys[start..stop].iter() {
+            let digit = self.get_digit(key);
+            self.new_indexes.push(self.offsets[digit]);
+            self.offsets[digit] += 1;
         }
 
         self.loop_index = stop;
 
-        return self.loop_index == self.to_sort.len();
+        return self.loop_index == self.keys.len();
     }
 
     fn move_items(&mut self) -> bool {
@@ -142,13 +345,31 @@ impl IncrementalSorter {
             let correct_position = self.new_indexes[idx];
 
             self.to_sort.swap(current_position, correct_position);
+            self.keys.swap(current_position, correct_position);
             self.new_indexes.swap(current_position, correct_position);
         }
         return false;
     }
-    
+
     pub fn sort(&mut self) -> bool {
         match self.state {
+            SorterState::Finished => {
+                // prepare() may have already detected a pre-sorted input
+                // and jumped straight here, skipping all radix work.
+                return true;
+            },
+
+            SorterState::PrefixSum => {
+                if self.prefix_sum() {
+                    // offsets now holds the per-bucket start offsets,
+                    // ready for compute_indexes to consume.
+                    self.state = SorterState::ComputeIndexes;
+                    self.loop_index = 0;
+                }
+
+                return false;
+            },
+
             SorterState::ComputeIndexes => {
                 if self.compute_indexes() {
                     // finished!
@@ -163,10 +384,10 @@ impl IncrementalSorter {
                 if !self.move_items() {
                     return false;
                 }
-                
+
                 self.digit_index += 1;
-                
-                if self.digit_index == self.max_digit_index {
+
+                if self.digit_index as usize == self.digit_positions.len() {
                     self.state = SorterState::Finished;
                     return true;
                 }
@@ -176,17 +397,19 @@ impl IncrementalSorter {
                 self.new_indexes.clear();
                 self.loop_index = 0;
                 self.accumulator = 0;
+                for count in self.histogram.iter_mut() {
+                    *count = 0;
+                }
 
                 return false;
             },
 
             SorterState::Counting => {
                 if self.bucket_counts() {
-                    // the count is finished!
-                    self.state = SorterState::ComputeIndexes;
+                    // the count is finished! move on to turning the
+                    // histogram into per-bucket start offsets.
+                    self.state = SorterState::PrefixSum;
                     self.loop_index = 0;
-                    self.false_count = 0;
-                    self.true_count = self.accumulator;
                 }
 
                 return false;
@@ -198,24 +421,464 @@ impl IncrementalSorter {
         };
     }
 
-    fn get_result(self) -> Vec<usize> {
+    fn get_result(self) -> Vec<T> {
+        return self.to_sort;
+    }
+}
+
+// a slice wrapper that lets multiple rayon tasks each write into their own
+// disjoint indices concurrently. Safety relies entirely on callers never
+// handing two tasks overlapping indices; see par_radix_pass, where the
+// per-chunk offsets are derived from an exclusive prefix sum and are
+// therefore guaranteed disjoint.
+#[cfg(feature = "rayon")]
+struct UnsafeSlice<'a, T> {
+    slice: &'a [UnsafeCell<T>]
+}
+
+#[cfg(feature = "rayon")]
+unsafe impl<'a, T: Send> Sync for UnsafeSlice<'a, T> {}
+
+#[cfg(feature = "rayon")]
+impl<'a, T> UnsafeSlice<'a, T> {
+    fn new(slice: &'a mut [T]) -> UnsafeSlice<'a, T> {
+        let ptr = slice as *mut [T] as *const [UnsafeCell<T>];
+        return UnsafeSlice { slice: unsafe { &*ptr } };
+    }
+
+    // safety: the caller must ensure no two tasks write the same index
+    // concurrently.
+    unsafe fn write(&self, i: usize, value: T) {
+        *self.slice[i].get() = value;
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<T: Send + Sync + Clone> IncrementalSorter<T> {
+    /// Runs one full radix digit pass using a parallel histogram and an
+    /// out-of-place parallel scatter, instead of the incremental
+    /// single-threaded `sort()` loop. Call in a loop, same as `sort()`,
+    /// until it returns `true`. Because each call does a whole pass (not
+    /// a bounded chunk of one), `iterations_per_call` instead sizes the
+    /// chunks handed to rayon, letting a caller amortize thread spawning
+    /// across a larger budget than the incremental path would use.
+    pub fn par_sort(&mut self) -> bool {
+        match self.state {
+            SorterState::Finished => true,
+
+            SorterState::Unprepared => {
+                panic!("Call to par_sort before prepare");
+            },
+
+            _ => {
+                self.par_radix_pass();
+                self.digit_index += 1;
+
+                if self.digit_index as usize == self.digit_positions.len() {
+                    self.state = SorterState::Finished;
+                    return true;
+                }
+
+                return false;
+            }
+        }
+    }
+
+    fn par_radix_pass(&mut self) {
+        let chunk_size = cmp::max(1, self.iterations_per_call);
+        let num_buckets = self.num_buckets;
+        let shift = self.digit_positions[self.digit_index as usize] as usize * self.digit_bits as usize;
+        let mask = num_buckets - 1;
+
+        // map-reduce: one histogram per chunk, computed in parallel
+        let chunk_histograms: Vec<Vec<usize>> = self.keys
+            .par_chunks(chunk_size)
+            .map(|chunk| {
+                let mut histogram = vec![0usize; num_buckets];
+                for &key in chunk {
+                    histogram[(key >> shift) & mask] += 1;
+                }
+                return histogram;
+            })
+            .collect();
+
+        // combine the per-chunk histograms into one, and turn it into
+        // global per-bucket start offsets, same as the sequential
+        // prefix_sum pass.
+        let num_chunks = chunk_histograms.len();
+        let mut bucket_start = vec![0usize; num_buckets];
+        let mut running_bucket = 0usize;
+        for b in 0..num_buckets {
+            bucket_start[b] = running_bucket;
+            for c in 0..num_chunks {
+                running_bucket += chunk_histograms[c][b];
+            }
+        }
+
+        // each chunk's contribution to bucket b starts right after every
+        // earlier chunk's contribution to bucket b, offset from that
+        // bucket's global start.
+        let mut chunk_offsets = vec![vec![0usize; num_buckets]; num_chunks];
+        let mut running = bucket_start;
+        for c in 0..num_chunks {
+            for b in 0..num_buckets {
+                chunk_offsets[c][b] = running[b];
+                running[b] += chunk_histograms[c][b];
+            }
+        }
+
+        if self.scratch_to_sort.len() != self.to_sort.len() {
+            self.scratch_to_sort = self.to_sort.clone();
+            self.scratch_keys = self.keys.clone();
+        }
+
+        // scatter: every chunk already knows the start offset of each of
+        // its buckets in the destination buffer, so chunks can write
+        // concurrently without ever touching the same index.
+        let dest_items = UnsafeSlice::new(&mut self.scratch_to_sort);
+        let dest_keys = UnsafeSlice::new(&mut self.scratch_keys);
+
+        self.to_sort.par_chunks(chunk_size)
+            .zip(self.keys.par_chunks(chunk_size))
+            .zip(chunk_offsets.into_par_iter())
+            .for_each(|((items, item_keys), mut offsets)| {
+                for (item, &key) in items.iter().zip(item_keys.iter()) {
+                    let digit = (key >> shift) & mask;
+                    let dest = offsets[digit];
+                    offsets[digit] += 1;
+                    unsafe {
+                        dest_items.write(dest, item.clone());
+                        dest_keys.write(dest, key);
+                    }
+                }
+            });
+
+        mem::swap(&mut self.to_sort, &mut self.scratch_to_sort);
+        mem::swap(&mut self.keys, &mut self.scratch_keys);
+    }
+}
+
+enum SelectState {
+    Unprepared,
+    Counting,
+    PrefixSum,
+    Partition,
+    Scatter,
+    Finished
+}
+
+/// Finds the element of a given rank (the `k`-th smallest) without fully
+/// sorting the input, by reusing the same bucket-counting machinery as
+/// `IncrementalSorter` MSD-first (highest digit first): each pass counts
+/// how many elements fall in every digit bucket of the current range,
+/// uses the prefix sums to find which single bucket contains the target
+/// rank, partitions just that range into buckets, and then narrows the
+/// range to the bucket that matters and recurses into its next lower
+/// digit -- discarding every other bucket instead of sorting it.
+pub struct IncrementalRadixSelect<T> {
+    iterations_per_call: usize,
+    to_sort: Vec<T>,
+    keys: Vec<usize>,
+    state: SelectState,
+
+    digit_bits: u8,
+    num_buckets: usize,
+
+    // total digit positions to consider, and the current digit position
+    // (counting down from num_digits - 1, i.e. most significant first)
+    num_digits: usize,
+    digit_index: usize,
+
+    // the half-open range still known to contain the target rank, and
+    // the bucket within it (found by prefix_sum) that holds it
+    lo: usize,
+    hi: usize,
+    target_rank: usize,
+    target_bucket: usize,
+    target_bucket_start: usize,
+
+    // shared
+    loop_index: usize,
+    accumulator: usize,
+
+    // histogram of digit values within [lo, hi), and the per-bucket
+    // start offsets (relative to lo) prefix_sum turns it into; offsets
+    // then doubles as a running per-bucket cursor during partition,
+    // same as IncrementalSorter's histogram/offsets pair
+    histogram: Vec<usize>,
+    offsets: Vec<usize>,
+
+    // the local (relative to lo) destination index partition assigns
+    // each element of [lo, hi)
+    new_indexes: Vec<usize>
+}
+
+impl<T> IncrementalRadixSelect<T> {
+    pub fn new(to_sort: Vec<T>, rank: usize) -> IncrementalRadixSelect<T> where T: RadixKey {
+        let keys = to_sort.iter().map(RadixKey::radix_key).collect();
+        return IncrementalRadixSelect::from_keys(to_sort, keys, rank, DEFAULT_DIGIT_BITS);
+    }
+
+    /// Selects by the order-preserving unsigned key that `key_fn` derives
+    /// from each item, same as `IncrementalSorter::with_key`.
+    pub fn with_key<K: RadixKey>(to_sort: Vec<T>, rank: usize, key_fn: impl Fn(&T) -> K) -> IncrementalRadixSelect<T> {
+        let keys = to_sort.iter().map(|itm| key_fn(itm).radix_key()).collect();
+        return IncrementalRadixSelect::from_keys(to_sort, keys, rank, DEFAULT_DIGIT_BITS);
+    }
+
+    pub fn with_iterations_per_call(to_sort: Vec<T>, rank: usize, iterations_per_call: usize) -> IncrementalRadixSelect<T> where T: RadixKey {
+        let mut to_return = IncrementalRadixSelect::new(to_sort, rank);
+        to_return.iterations_per_call = iterations_per_call;
+        return to_return;
+    }
+
+    fn from_keys(to_sort: Vec<T>, keys: Vec<usize>, rank: usize, digit_bits: u8) -> IncrementalRadixSelect<T> {
+        // the RadixKey impls for u64/i64/f64 assume a 64-bit usize
+        // and would silently truncate keys on a narrower target.
+        debug_assert_eq!(mem::size_of::<usize>(), 8);
+
+        let len = to_sort.len();
+        if rank >= len {
+            panic!("IncrementalRadixSelect rank must be less than the number of elements");
+        }
+
+        let num_buckets = 1usize << digit_bits;
+        return IncrementalRadixSelect { to_sort, keys, state: SelectState::Unprepared,
+                                         digit_bits, num_buckets,
+                                         num_digits: 0, digit_index: 0,
+                                         lo: 0, hi: len, target_rank: rank,
+                                         target_bucket: 0, target_bucket_start: 0,
+                                         loop_index: 0, accumulator: 0,
+                                         histogram: vec![0; num_buckets],
+                                         offsets: vec![0; num_buckets],
+                                         new_indexes: Vec::new(),
+                                         iterations_per_call: 32 };
+    }
+
+    pub fn prepare(&mut self) {
+        if let SelectState::Unprepared = self.state {
+            let total_bits = usize::min_value().count_zeros() + usize::min_value().count_ones();
+            let fewest_leading_zeros = self.keys.iter().map(|key| key.leading_zeros()).min().unwrap_or(total_bits);
+            let bits_needed = (total_bits - fewest_leading_zeros) as usize;
+            let digit_bits = self.digit_bits as usize;
+            self.num_digits = (bits_needed + digit_bits - 1) / digit_bits;
+
+            if self.num_digits == 0 || self.hi - self.lo <= 1 {
+                // nothing to do: every key is identical, or there's only
+                // one candidate left
+                self.state = SelectState::Finished;
+                return;
+            }
+
+            self.digit_index = self.num_digits - 1;
+            self.loop_index = self.lo;
+            self.state = SelectState::Counting;
+            return;
+        }
+
+        panic!("Call to IncrementalRadixSelect prepare when not in the unprepared state");
+    }
+
+    fn get_digit(&self, key: usize) -> usize {
+        let shift = self.digit_index * self.digit_bits as usize;
+        (key >> shift) & (self.num_buckets - 1)
+    }
+
+    // tallies a histogram of digit values over the next chunk of [lo, hi)
+    fn bucket_counts(&mut self) -> bool {
+        let start = self.loop_index;
+        let stop = cmp::min(start + self.iterations_per_call, self.hi);
+
+        for &key in self.keys[start..stop].iter() {
+            let digit = self.get_digit(key);
+            self.histogram[digit] += 1;
+        }
+
+        self.loop_index = stop;
+        return stop == self.hi;
+    }
+
+    // turns the histogram into per-bucket start offsets (relative to lo)
+    fn prefix_sum(&mut self) -> bool {
+        let start = self.loop_index;
+        let stop = cmp::min(start + self.iterations_per_call, self.num_buckets);
+
+        for bucket in start..stop {
+            self.offsets[bucket] = self.accumulator;
+            self.accumulator += self.histogram[bucket];
+        }
+
+        self.loop_index = stop;
+        return stop == self.num_buckets;
+    }
+
+    // finds the single bucket whose range covers target_rank
+    fn locate_target_bucket(&mut self) {
+        let rel_rank = self.target_rank - self.lo;
+        for bucket in 0..self.num_buckets {
+            if rel_rank < self.offsets[bucket] + self.histogram[bucket] {
+                self.target_bucket = bucket;
+                self.target_bucket_start = self.offsets[bucket];
+                return;
+            }
+        }
+
+        unreachable!("rank not covered by any digit bucket");
+    }
+
+    // assigns each element of [lo, hi) its local destination index,
+    // using offsets as a running per-bucket cursor
+    fn partition(&mut self) -> bool {
+        let start = self.loop_index;
+        let stop = cmp::min(start + self.iterations_per_call, self.hi);
+
+        for &key in self.keys[start..stop].iter() {
+            let digit = self.get_digit(key);
+            self.new_indexes.push(self.offsets[digit]);
+            self.offsets[digit] += 1;
+        }
+
+        self.loop_index = stop;
+        return stop == self.hi;
+    }
+
+    fn scatter(&mut self) -> bool {
+        for _ in 0..self.iterations_per_call {
+            let idx = self.loop_index - self.lo;
+
+            if self.new_indexes[idx] == idx {
+                self.loop_index += 1;
+                if self.loop_index == self.hi {
+                    return true;
+                }
+            }
+
+            let current_local = idx;
+            let correct_local = self.new_indexes[idx];
+
+            self.to_sort.swap(self.lo + current_local, self.lo + correct_local);
+            self.keys.swap(self.lo + current_local, self.lo + correct_local);
+            self.new_indexes.swap(current_local, correct_local);
+        }
+        return false;
+    }
+
+    pub fn step(&mut self) -> bool {
+        match self.state {
+            SelectState::Finished => {
+                // prepare() may have already found the target within a
+                // single-element range and jumped straight here.
+                return true;
+            },
+
+            SelectState::Counting => {
+                if self.bucket_counts() {
+                    self.state = SelectState::PrefixSum;
+                    self.loop_index = 0;
+                    self.accumulator = 0;
+                }
+                return false;
+            },
+
+            SelectState::PrefixSum => {
+                if self.prefix_sum() {
+                    self.locate_target_bucket();
+                    self.state = SelectState::Partition;
+                    self.loop_index = self.lo;
+                }
+                return false;
+            },
+
+            SelectState::Partition => {
+                if self.partition() {
+                    debug_assert_eq!(self.new_indexes.len(), self.hi - self.lo);
+                    self.state = SelectState::Scatter;
+                    self.loop_index = self.lo;
+                }
+                return false;
+            },
+
+            SelectState::Scatter => {
+                if !self.scatter() {
+                    return false;
+                }
+
+                // narrow the range to the bucket that holds target_rank
+                self.lo += self.target_bucket_start;
+                self.hi = self.lo + self.histogram[self.target_bucket];
+
+                if self.digit_index == 0 || self.hi - self.lo <= 1 {
+                    self.state = SelectState::Finished;
+                    return true;
+                }
+
+                self.digit_index -= 1;
+                self.loop_index = self.lo;
+                self.new_indexes.clear();
+                self.accumulator = 0;
+                for count in self.histogram.iter_mut() {
+                    *count = 0;
+                }
+
+                self.state = SelectState::Counting;
+                return false;
+            },
+
+            SelectState::Unprepared => {
+                panic!("Call to IncrementalRadixSelect step before prepare");
+            }
+        }
+    }
+
+    /// The element of the requested rank. Only meaningful once `step()`
+    /// has returned `true`.
+    pub fn result(&self) -> &T {
+        &self.to_sort[self.target_rank]
+    }
+
+    fn into_to_sort(self) -> Vec<T> {
         return self.to_sort;
     }
+
+    /// Returns the `k` smallest elements of `to_sort`, in sorted order,
+    /// without fully sorting the rest: partitions around rank `k` by
+    /// running one selection for that rank, then runs `IncrementalSorter` over just the
+    /// surviving prefix.
+    pub fn top_k(mut to_sort: Vec<T>, k: usize) -> Vec<T> where T: RadixKey {
+        let k = cmp::min(k, to_sort.len());
+        if k == 0 {
+            return Vec::new();
+        }
+
+        if k < to_sort.len() {
+            let mut selector = IncrementalRadixSelect::new(to_sort, k);
+            selector.prepare();
+            while !selector.step() {};
+            to_sort = selector.into_to_sort();
+        }
+
+        to_sort.truncate(k);
+        let mut sorter = IncrementalSorter::new(to_sort);
+        sorter.prepare();
+        while !sorter.sort() {};
+        return sorter.get_result();
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
     use IncrementalSorter;
+    use IncrementalRadixSelect;
     use rand::prelude::*;
 
     fn compare_with_stdlib_with_calls(data: &Vec<usize>, calls: usize) {
         let cpy1 = data.clone();
         let mut cpy2 = data.clone();
-        
+
         let mut incr_sort = IncrementalSorter::new(cpy1);
         incr_sort.prepare();
-        
+
         while !incr_sort.sort() {};
 
         let sorted_data = incr_sort.get_result();
@@ -223,13 +886,13 @@ mod tests {
         cpy2.sort();
         assert_eq!(cpy2, sorted_data);
     }
-    
+
     fn compare_with_stdlib(data: &Vec<usize>) {
         compare_with_stdlib_with_calls(data, 1);
         compare_with_stdlib_with_calls(data, 2);
         compare_with_stdlib_with_calls(data, 64);
     }
-    
+
     #[test]
     fn simple_example() {
         let data = vec![10, 20, 30000, 30, 5, 1, 90, 128];
@@ -241,7 +904,7 @@ mod tests {
         let data = vec![30, 30, 30, 30, 30];
         compare_with_stdlib(&data);
     }
-    
+
     #[test]
     fn random_size_500() {
         for _ in 0..100 {
@@ -266,4 +929,178 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn par_sort_matches_stdlib() {
+        for _ in 0..5 {
+            let mut data = Vec::new();
+            for _ in 0..5000 {
+                let v = random::<f64>();
+                data.push((v * 10000.0) as usize);
+            }
+
+            let mut cpy2 = data.clone();
+            let mut incr_sort = IncrementalSorter::new(data);
+            incr_sort.prepare();
+
+            while !incr_sort.par_sort() {};
+
+            let sorted_data = incr_sort.get_result();
+            cpy2.sort();
+            assert_eq!(cpy2, sorted_data);
+        }
+    }
+
+    #[test]
+    fn small_digit_bits() {
+        // force several passes over a tiny bucket count (4 buckets) to
+        // exercise the prefix-sum machinery beyond the default k = 8
+        let data = vec![10, 20, 30000, 30, 5, 1, 90, 128, 0, 7];
+        let mut cpy2 = data.clone();
+
+        let mut incr_sort = IncrementalSorter::with_digit_bits(data, 2);
+        incr_sort.prepare();
+
+        while !incr_sort.sort() {};
+
+        let sorted_data = incr_sort.get_result();
+        cpy2.sort();
+        assert_eq!(cpy2, sorted_data);
+    }
+
+    #[test]
+    fn signed_integers() {
+        let data = vec![-5_i32, 3, 0, -100, 42, -1, 17];
+        let mut cpy2 = data.clone();
+
+        let mut incr_sort = IncrementalSorter::new(data);
+        incr_sort.prepare();
+
+        while !incr_sort.sort() {};
+
+        let sorted_data = incr_sort.get_result();
+        cpy2.sort();
+        assert_eq!(cpy2, sorted_data);
+    }
+
+    #[test]
+    fn floats() {
+        let data = vec![-5.5_f64, 3.25, 0.0, -100.1, 42.0, -1.0, 17.75];
+        let mut cpy2 = data.clone();
+
+        let mut incr_sort = IncrementalSorter::new(data);
+        incr_sort.prepare();
+
+        while !incr_sort.sort() {};
+
+        let sorted_data = incr_sort.get_result();
+        cpy2.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(cpy2, sorted_data);
+    }
+
+    struct Person {
+        age: u32,
+        name: &'static str
+    }
+
+    #[test]
+    fn sort_by_key() {
+        let data = vec![
+            Person { age: 42, name: "alice" },
+            Person { age: 7, name: "bob" },
+            Person { age: 30, name: "carol" },
+        ];
+
+        let mut incr_sort = IncrementalSorter::with_key(data, |p: &Person| p.age);
+        incr_sort.prepare();
+
+        while !incr_sort.sort() {};
+
+        let sorted_data = incr_sort.get_result();
+        let names: Vec<&str> = sorted_data.iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["bob", "carol", "alice"]);
+    }
+
+    #[test]
+    fn detects_pre_sorted_input() {
+        let data = vec![1, 2, 2, 3, 10, 10, 1000];
+        let mut incr_sort = IncrementalSorter::new(data);
+        incr_sort.prepare();
+        assert!(incr_sort.was_pre_sorted());
+
+        while !incr_sort.sort() {};
+        assert_eq!(incr_sort.get_result(), vec![1, 2, 2, 3, 10, 10, 1000]);
+    }
+
+    #[test]
+    fn unsorted_input_is_not_pre_sorted() {
+        let mut incr_sort = IncrementalSorter::new(vec![5, 2, 8, 1]);
+        incr_sort.prepare();
+        assert!(!incr_sort.was_pre_sorted());
+    }
+
+    #[test]
+    fn clustered_values_skip_constant_digits() {
+        // every value shares the same high byte, so only the low digit
+        // position should survive prepare()'s AND/OR scan
+        let data = vec![0x1200, 0x12ff, 0x1250, 0x1201, 0x12aa];
+        let mut cpy2 = data.clone();
+
+        let mut incr_sort = IncrementalSorter::with_digit_bits(data, 8);
+        incr_sort.prepare();
+        assert_eq!(incr_sort.digit_passes(), 1);
+
+        while !incr_sort.sort() {};
+
+        let sorted_data = incr_sort.get_result();
+        cpy2.sort();
+        assert_eq!(cpy2, sorted_data);
+    }
+
+    #[test]
+    fn select_nth_matches_stdlib_sort() {
+        let mut rng = thread_rng();
+        let data: Vec<usize> = (0..500).map(|_| rng.gen_range(0, 10000)).collect();
+
+        let mut sorted = data.clone();
+        sorted.sort();
+
+        for &rank in &[0, 1, 17, 249, 498, 499] {
+            let mut selector = IncrementalRadixSelect::new(data.clone(), rank);
+            selector.prepare();
+            while !selector.step() {};
+            assert_eq!(*selector.result(), sorted[rank]);
+        }
+    }
+
+    #[test]
+    fn select_nth_single_element() {
+        let mut selector = IncrementalRadixSelect::new(vec![42], 0);
+        selector.prepare();
+        while !selector.step() {};
+        assert_eq!(*selector.result(), 42);
+    }
+
+    #[test]
+    fn top_k_matches_stdlib_prefix() {
+        let mut rng = thread_rng();
+        let data: Vec<usize> = (0..300).map(|_| rng.gen_range(0, 10000)).collect();
+
+        let mut sorted = data.clone();
+        sorted.sort();
+
+        let k = 42;
+        let smallest_k = IncrementalRadixSelect::top_k(data, k);
+        assert_eq!(smallest_k, sorted[..k]);
+    }
+
+    #[test]
+    fn top_k_larger_than_input_returns_everything_sorted() {
+        let data = vec![5, 3, 8, 1, 9];
+        let mut sorted = data.clone();
+        sorted.sort();
+
+        assert_eq!(IncrementalRadixSelect::top_k(data, 100), sorted);
+    }
+
 }